@@ -0,0 +1,183 @@
+//! Sound playback, e.g. for engine and collision sounds.
+//!
+//! Without the `audio` feature, or if there is no audio output device, everything in here is a
+//! no-op, so the game runs the same, just silently.
+
+use crate::math::Vec3;
+#[cfg(feature = "audio")]
+use anyhow::{Context, Result};
+#[cfg(feature = "audio")]
+use log::warn;
+#[cfg(feature = "audio")]
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Source, SpatialSink};
+#[cfg(feature = "audio")]
+use std::fs::File;
+#[cfg(feature = "audio")]
+use std::io::BufReader;
+use std::path::Path;
+
+/// Distance between the ears of the [Listener], in world units.
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+const EAR_DISTANCE: f32 = 0.2;
+
+/// Where sounds are heard from, usually the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Listener {
+    pub position: Vec3,
+    /// Direction from the left to the right ear.
+    pub right: Vec3,
+}
+
+impl Default for Listener {
+    fn default() -> Self {
+        Listener {
+            position: Vec3::ZERO,
+            right: Vec3::X,
+        }
+    }
+}
+
+impl Listener {
+    #[cfg(feature = "audio")]
+    fn ears(&self) -> ([f32; 3], [f32; 3]) {
+        let offset = self.right.normalize_or_zero() * (EAR_DISTANCE / 2.0);
+        (
+            (self.position - offset).to_array(),
+            (self.position + offset).to_array(),
+        )
+    }
+}
+
+/// Plays sounds on the default audio output device. Owned by the app, independent of the render
+/// loop.
+pub struct Audio {
+    /// The stream stops playing when it is dropped, so it is kept alongside its handle
+    #[cfg(feature = "audio")]
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    listener: Listener,
+}
+
+impl Audio {
+    #[cfg(feature = "audio")]
+    pub fn new() -> Self {
+        let output = match OutputStream::try_default() {
+            Ok(output) => Some(output),
+            Err(err) => {
+                warn!("Audio is not available: {}", err);
+                None
+            }
+        };
+        Audio {
+            output,
+            listener: Listener::default(),
+        }
+    }
+
+    #[cfg(not(feature = "audio"))]
+    pub fn new() -> Self {
+        Audio {
+            listener: Listener::default(),
+        }
+    }
+
+    pub fn listener(&self) -> Listener {
+        self.listener
+    }
+
+    /// Moves the listener. Looping sounds pick this up with their next
+    /// [LoopingSound::set_position].
+    pub fn set_listener(&mut self, listener: Listener) {
+        self.listener = listener;
+    }
+
+    /// Plays a sound file once, at the position of the listener.
+    pub fn play_sound(&self, path: &Path) {
+        self.play_sound_at(path, self.listener.position);
+    }
+
+    /// Plays a sound file once, panned according to where `position` is relative to the
+    /// listener. Files that can't be loaded are skipped with a warning.
+    #[cfg_attr(not(feature = "audio"), allow(unused_variables))]
+    pub fn play_sound_at(&self, path: &Path, position: Vec3) {
+        #[cfg(feature = "audio")]
+        if let Some(sink) = self.spatial_sink(path, position, false) {
+            sink.detach();
+        }
+    }
+
+    /// Starts playing a sound file in a loop, e.g. an engine sound. It stops when the returned
+    /// handle is dropped.
+    #[cfg_attr(not(feature = "audio"), allow(unused_variables))]
+    pub fn play_looping(&self, path: &Path, position: Vec3) -> LoopingSound {
+        LoopingSound {
+            #[cfg(feature = "audio")]
+            sink: self.spatial_sink(path, position, true),
+        }
+    }
+
+    #[cfg(feature = "audio")]
+    fn spatial_sink(&self, path: &Path, position: Vec3, looping: bool) -> Option<SpatialSink> {
+        let (_, handle) = self.output.as_ref()?;
+        let load = || -> Result<SpatialSink> {
+            let file =
+                File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+            let source = Decoder::new(BufReader::new(file))
+                .with_context(|| format!("Could not decode {}", path.display()))?;
+            let (left_ear, right_ear) = self.listener.ears();
+            let sink = SpatialSink::try_new(handle, position.to_array(), left_ear, right_ear)?;
+            if looping {
+                sink.append(source.repeat_infinite());
+            } else {
+                sink.append(source);
+            }
+            Ok(sink)
+        };
+        load()
+            .map_err(|err| warn!("Could not play a sound: {:#}", err))
+            .ok()
+    }
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Audio::new()
+    }
+}
+
+/// A sound that plays in a loop until it is dropped.
+pub struct LoopingSound {
+    #[cfg(feature = "audio")]
+    sink: Option<SpatialSink>,
+}
+
+impl LoopingSound {
+    /// Playback speed, which also changes the pitch. E.g. tie this to the engine RPM.
+    #[cfg_attr(not(feature = "audio"), allow(unused_variables))]
+    pub fn set_pitch(&self, pitch: f32) {
+        #[cfg(feature = "audio")]
+        if let Some(sink) = &self.sink {
+            sink.set_speed(pitch);
+        }
+    }
+
+    #[cfg_attr(not(feature = "audio"), allow(unused_variables))]
+    pub fn set_volume(&self, volume: f32) {
+        #[cfg(feature = "audio")]
+        if let Some(sink) = &self.sink {
+            sink.set_volume(volume);
+        }
+    }
+
+    /// Moves the source of the sound, and updates the panning to the current listener of
+    /// `audio`.
+    #[cfg_attr(not(feature = "audio"), allow(unused_variables))]
+    pub fn set_position(&self, audio: &Audio, position: Vec3) {
+        #[cfg(feature = "audio")]
+        if let Some(sink) = &self.sink {
+            let (left_ear, right_ear) = audio.listener.ears();
+            sink.set_emitter_position(position.to_array());
+            sink.set_left_ear_position(left_ear);
+            sink.set_right_ear_position(right_ear);
+        }
+    }
+}
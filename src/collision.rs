@@ -0,0 +1,132 @@
+//! Simple intersection tests for gameplay, e.g. whether the car touches a trackside object.
+
+use crate::math::{Mat4, Vec3};
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    /// Smallest box containing all points. `None` if there are no points.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        points.into_iter().fold(None, |aabb, point| {
+            Some(match aabb {
+                Some(Aabb { min, max }) => Aabb::new(min.min(point), max.max(point)),
+                None => Aabb::new(point, point),
+            })
+        })
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Smallest axis aligned box enclosing this box after transforming it by `matrix`.
+    pub fn transformed(&self, matrix: &Mat4) -> Aabb {
+        let corners = (0..8).map(|i| {
+            matrix.transform_point3(Vec3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            ))
+        });
+        Aabb::from_points(corners).unwrap()
+    }
+}
+
+/// Whether the boxes overlap. Boxes that only touch count as intersecting.
+pub fn intersects(a: &Aabb, b: &Aabb) -> bool {
+    a.min.cmple(b.max).all() && b.min.cmple(a.max).all()
+}
+
+/// Distance along the ray to the point where it enters the box, in multiples of `direction`.
+/// Returns 0 if the ray starts inside the box and `None` if it misses it.
+pub fn ray_aabb(origin: Vec3, direction: Vec3, aabb: &Aabb) -> Option<f32> {
+    // slab test: intersect the ranges of the ray inside each pair of parallel planes
+    let mut near = f32::NEG_INFINITY;
+    let mut far = f32::INFINITY;
+    for axis in 0..3 {
+        let (origin, direction) = (origin[axis], direction[axis]);
+        let (min, max) = (aabb.min[axis], aabb.max[axis]);
+        if direction == 0.0 {
+            // parallel to the slab, either always or never inside it
+            if origin < min || origin > max {
+                return None;
+            }
+            continue;
+        }
+        let t1 = (min - origin) / direction;
+        let t2 = (max - origin) / direction;
+        near = near.max(t1.min(t2));
+        far = far.min(t1.max(t2));
+    }
+    (near <= far && far >= 0.0).then_some(near.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Quat;
+    use std::f32::consts::FRAC_PI_4;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::ZERO, Vec3::ONE)
+    }
+
+    #[test]
+    fn touching_boxes_intersect() {
+        let other = Aabb::new(Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.0, 1.0, 1.0));
+        assert!(intersects(&unit_box(), &other));
+        assert!(intersects(&other, &unit_box()));
+
+        let apart = Aabb::new(Vec3::new(1.1, 0.0, 0.0), Vec3::new(2.0, 1.0, 1.0));
+        assert!(!intersects(&unit_box(), &apart));
+    }
+
+    #[test]
+    fn ray_from_inside_hits_at_zero() {
+        let hit = ray_aabb(Vec3::splat(0.5), Vec3::new(1.0, 2.0, -3.0), &unit_box());
+        assert_eq!(hit, Some(0.0));
+    }
+
+    #[test]
+    fn ray_misses_box_behind_origin() {
+        let hit = ray_aabb(Vec3::new(3.0, 0.5, 0.5), Vec3::X, &unit_box());
+        assert_eq!(hit, None);
+        let hit = ray_aabb(Vec3::new(3.0, 0.5, 0.5), -Vec3::X, &unit_box());
+        assert_eq!(hit, Some(2.0));
+    }
+
+    #[test]
+    fn axis_parallel_ray_on_face() {
+        // runs along the face at x = 0, the zero x component must not give NaN
+        let hit = ray_aabb(Vec3::new(0.0, 0.5, -2.0), Vec3::Z, &unit_box());
+        assert_eq!(hit, Some(2.0));
+        // parallel to the box, but outside of it
+        let hit = ray_aabb(Vec3::new(-0.1, 0.5, -2.0), Vec3::Z, &unit_box());
+        assert_eq!(hit, None);
+    }
+
+    #[test]
+    fn transformed_with_rotation() {
+        let matrix = Mat4::from_rotation_translation(
+            Quat::from_rotation_z(FRAC_PI_4),
+            Vec3::new(0.0, 0.0, 5.0),
+        );
+        let aabb = Aabb::new(Vec3::splat(-1.0), Vec3::ONE).transformed(&matrix);
+        let half_diagonal = 2.0_f32.sqrt();
+        let expected = Aabb::new(
+            Vec3::new(-half_diagonal, -half_diagonal, 4.0),
+            Vec3::new(half_diagonal, half_diagonal, 6.0),
+        );
+        assert!(aabb.min.abs_diff_eq(expected.min, 1e-5), "{:?}", aabb);
+        assert!(aabb.max.abs_diff_eq(expected.max, 1e-5), "{:?}", aabb);
+    }
+}
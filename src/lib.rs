@@ -0,0 +1,6 @@
+pub mod audio;
+pub mod collision;
+pub mod math;
+pub mod renderer;
+pub mod rng;
+pub mod scene;
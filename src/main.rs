@@ -1,23 +1,27 @@
-mod renderer;
-
-use crate::renderer::main_loop::{main_loop, App, DrawContext, RenderLoopSettings};
-use log::info;
+use log::debug;
+use racing_game::renderer::main_loop::{main_loop, App, DrawContext, RenderLoopSettings};
 
 pub fn main() {
     dotenv::dotenv().ok();
+    #[cfg(feature = "logger")]
     pretty_env_logger::init();
 
-    let settings = RenderLoopSettings::default();
+    let settings = RenderLoopSettings {
+        window_title: "Racing Game".to_string(),
+        show_fps_in_title: true,
+        ..Default::default()
+    };
     let app = TestApp;
-    main_loop(settings, app);
+    let Err(err) = main_loop(settings, app);
+    debug!("Failed to start the renderer: {:?}", err);
+    eprintln!("Error: {:#}", err);
+    std::process::exit(1);
 }
 
 struct TestApp;
 
 impl App for TestApp {
-    fn draw(&mut self, context: &mut DrawContext) {
-        println!("Frame!")
-    }
+    fn draw(&mut self, _context: &mut DrawContext) {}
 }
 
 impl Drop for TestApp {
@@ -0,0 +1,24 @@
+//! Vector and matrix math, shared by everything that deals with positions and transforms.
+//!
+//! World space is right handed with the Y axis pointing up. Projection matrices map into the
+//! vulkan clip space, where the Y axis points down and depth goes from 0 to 1.
+
+pub use glam::{Mat4, Quat, Vec2, Vec3, Vec4};
+
+/// Perspective projection matrix. The vertical field of view `fov_y` is given in radians.
+pub fn perspective(fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> Mat4 {
+    let mut projection = Mat4::perspective_rh(fov_y, aspect_ratio, near, far);
+    // vulkan's Y axis points down
+    projection.y_axis.y *= -1.0;
+    projection
+}
+
+/// View matrix for a camera at `eye`, looking at `target`.
+pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Mat4 {
+    Mat4::look_at_rh(eye, target, up)
+}
+
+/// Model matrix that scales, then rotates and then translates.
+pub fn from_translation_rotation_scale(translation: Vec3, rotation: Quat, scale: Vec3) -> Mat4 {
+    Mat4::from_scale_rotation_translation(scale, rotation, translation)
+}
@@ -1,6 +1,9 @@
 use ash::vk;
 use ash::vk::DebugUtilsMessageSeverityFlagsEXT;
 use log::log;
+use std::borrow::Cow;
+use std::ffi::CStr;
+use std::os::raw::c_char;
 
 /// Callback for the validation layer
 pub unsafe extern "system" fn vulkan_debug_utils_callback(
@@ -10,7 +13,9 @@ pub unsafe extern "system" fn vulkan_debug_utils_callback(
     _p_user_data: *mut std::ffi::c_void,
 ) -> vk::Bool32 {
     // tudo use log crate
-    let message = std::ffi::CStr::from_ptr((*p_callback_data).p_message);
+    let callback_data = &*p_callback_data;
+    let message = c_str_or_empty(callback_data.p_message);
+    let message_id_name = c_str_or_empty(callback_data.p_message_id_name);
     let ty = format!("{:?}", message_type).to_lowercase();
     let severity = match message_severity {
         DebugUtilsMessageSeverityFlagsEXT::ERROR => log::Level::Error,
@@ -19,6 +24,53 @@ pub unsafe extern "system" fn vulkan_debug_utils_callback(
         DebugUtilsMessageSeverityFlagsEXT::WARNING => log::Level::Warn,
         _ => log::Level::Info,
     };
-    log!(severity, "[{}] {:?}", ty, message);
+
+    // names of the objects involved, if the application labeled them
+    let objects = if callback_data.p_objects.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(callback_data.p_objects, callback_data.object_count as usize)
+    };
+    let object_labels = objects
+        .iter()
+        .filter(|it| !it.p_object_name.is_null())
+        .map(|it| {
+            format!(
+                "{:?} '{}'",
+                it.object_type,
+                c_str_or_empty(it.p_object_name)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if object_labels.is_empty() {
+        log!(
+            severity,
+            "[{}] [{} ({:#x})] {}",
+            ty,
+            message_id_name,
+            callback_data.message_id_number,
+            message
+        );
+    } else {
+        log!(
+            severity,
+            "[{}] [{} ({:#x})] [objects: {}] {}",
+            ty,
+            message_id_name,
+            callback_data.message_id_number,
+            object_labels.join(", "),
+            message
+        );
+    }
     vk::FALSE
 }
+
+/// Converts a possibly null C string from the validation layer.
+unsafe fn c_str_or_empty<'a>(ptr: *const c_char) -> Cow<'a, str> {
+    if ptr.is_null() {
+        Cow::Borrowed("")
+    } else {
+        CStr::from_ptr(ptr).to_string_lossy()
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Time span over which the frame rate is averaged.
+const AVERAGING_WINDOW: Duration = Duration::from_secs(1);
+
+/// Measures the frame rate as a rolling average over the last second.
+pub struct FpsCounter {
+    frame_times: VecDeque<Instant>,
+}
+
+impl FpsCounter {
+    pub fn new() -> Self {
+        FpsCounter {
+            frame_times: VecDeque::new(),
+        }
+    }
+
+    /// Records that a new frame started at the given time.
+    pub fn frame(&mut self, now: Instant) {
+        self.frame_times.push_back(now);
+        while let Some(&oldest) = self.frame_times.front() {
+            if now.duration_since(oldest) <= AVERAGING_WINDOW {
+                break;
+            }
+            self.frame_times.pop_front();
+        }
+    }
+
+    /// The average number of frames per second, or 0 if not enough frames were recorded yet.
+    pub fn fps(&self) -> f32 {
+        match (self.frame_times.front(), self.frame_times.back()) {
+            (Some(&first), Some(&last)) if last > first => {
+                (self.frame_times.len() - 1) as f32 / last.duration_since(first).as_secs_f32()
+            }
+            _ => 0.0,
+        }
+    }
+}
@@ -0,0 +1,185 @@
+#[cfg(feature = "gamepad")]
+use gilrs::{EventType, Gamepad, GamepadId, Gilrs};
+#[cfg(feature = "gamepad")]
+use log::{info, warn};
+
+/// Analog axis of a gamepad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    /// Left trigger, on gamepads that report it as an axis.
+    LeftZ,
+    /// Right trigger, on gamepads that report it as an axis.
+    RightZ,
+    DPadX,
+    DPadY,
+}
+
+/// Button of a gamepad. The face buttons are named by their position, e.g. `South` is A on an
+/// Xbox controller and Cross on a PlayStation controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    Mode,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[cfg(feature = "gamepad")]
+impl From<Axis> for gilrs::Axis {
+    fn from(axis: Axis) -> Self {
+        match axis {
+            Axis::LeftStickX => gilrs::Axis::LeftStickX,
+            Axis::LeftStickY => gilrs::Axis::LeftStickY,
+            Axis::RightStickX => gilrs::Axis::RightStickX,
+            Axis::RightStickY => gilrs::Axis::RightStickY,
+            Axis::LeftZ => gilrs::Axis::LeftZ,
+            Axis::RightZ => gilrs::Axis::RightZ,
+            Axis::DPadX => gilrs::Axis::DPadX,
+            Axis::DPadY => gilrs::Axis::DPadY,
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+impl From<Button> for gilrs::Button {
+    fn from(button: Button) -> Self {
+        match button {
+            Button::South => gilrs::Button::South,
+            Button::East => gilrs::Button::East,
+            Button::North => gilrs::Button::North,
+            Button::West => gilrs::Button::West,
+            Button::LeftTrigger => gilrs::Button::LeftTrigger,
+            Button::LeftTrigger2 => gilrs::Button::LeftTrigger2,
+            Button::RightTrigger => gilrs::Button::RightTrigger,
+            Button::RightTrigger2 => gilrs::Button::RightTrigger2,
+            Button::Select => gilrs::Button::Select,
+            Button::Start => gilrs::Button::Start,
+            Button::Mode => gilrs::Button::Mode,
+            Button::LeftThumb => gilrs::Button::LeftThumb,
+            Button::RightThumb => gilrs::Button::RightThumb,
+            Button::DPadUp => gilrs::Button::DPadUp,
+            Button::DPadDown => gilrs::Button::DPadDown,
+            Button::DPadLeft => gilrs::Button::DPadLeft,
+            Button::DPadRight => gilrs::Button::DPadRight,
+        }
+    }
+}
+
+/// Keeps track of the connected gamepads and of the one that is currently used.
+///
+/// Without the `gamepad` feature, no gamepads are ever reported, all axes are at 0 and no button
+/// is pressed.
+pub struct Gamepads {
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<Gilrs>,
+    #[cfg(feature = "gamepad")]
+    active: Option<GamepadId>,
+}
+
+impl Gamepads {
+    #[cfg(feature = "gamepad")]
+    pub fn new() -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(err) => {
+                warn!("Gamepad support is not available: {}", err);
+                None
+            }
+        };
+        let active = gilrs
+            .as_ref()
+            .and_then(|gilrs| gilrs.gamepads().next().map(|(id, _)| id));
+        Gamepads { gilrs, active }
+    }
+
+    #[cfg(not(feature = "gamepad"))]
+    pub fn new() -> Self {
+        Gamepads {}
+    }
+
+    /// Processes the pending gamepad events, must be called once per frame.
+    ///
+    /// The gamepad on which a button was pressed last becomes the active one. If the active
+    /// gamepad is disconnected, another connected gamepad takes over.
+    pub fn poll(&mut self) {
+        #[cfg(feature = "gamepad")]
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(event) = gilrs.next_event() {
+                match event.event {
+                    EventType::Connected => {
+                        info!("Gamepad connected: {}", gilrs.gamepad(event.id).name());
+                        self.active.get_or_insert(event.id);
+                    }
+                    EventType::Disconnected => {
+                        info!("Gamepad disconnected: {}", gilrs.gamepad(event.id).name());
+                        if self.active == Some(event.id) {
+                            self.active = gilrs
+                                .gamepads()
+                                .map(|(id, _)| id)
+                                .find(|&id| id != event.id);
+                        }
+                    }
+                    EventType::ButtonPressed(..) => self.active = Some(event.id),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// The gamepad that is currently used, if any is connected.
+    #[cfg(feature = "gamepad")]
+    fn active(&self) -> Option<Gamepad<'_>> {
+        let gamepad = self.gilrs.as_ref()?.gamepad(self.active?);
+        gamepad.is_connected().then_some(gamepad)
+    }
+
+    /// Position of an axis of the active gamepad, between -1 and 1. 0 if there is none.
+    #[cfg_attr(not(feature = "gamepad"), allow(unused_variables))]
+    pub fn axis(&self, axis: Axis) -> f32 {
+        #[cfg(feature = "gamepad")]
+        if let Some(gamepad) = self.active() {
+            return gamepad.value(axis.into());
+        }
+        0.0
+    }
+
+    /// Whether a button of the active gamepad is pressed.
+    #[cfg_attr(not(feature = "gamepad"), allow(unused_variables))]
+    pub fn button(&self, button: Button) -> bool {
+        #[cfg(feature = "gamepad")]
+        if let Some(gamepad) = self.active() {
+            return gamepad.is_pressed(button.into());
+        }
+        false
+    }
+
+    /// How far a button of the active gamepad is pressed, between 0 and 1.
+    #[cfg_attr(not(feature = "gamepad"), allow(unused_variables))]
+    pub fn button_value(&self, button: Button) -> f32 {
+        #[cfg(feature = "gamepad")]
+        if let Some(data) = self
+            .active()
+            .and_then(|gamepad| gamepad.button_data(button.into()).map(|it| it.value()))
+        {
+            return data;
+        }
+        0.0
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{Context, Result};
+use ash::{vk, Device};
+use log::info;
+use std::collections::HashMap;
+
+/// Maximum number of passes that can be timed per frame.
+const MAX_PASSES: u32 = 8;
+
+/// Measures how long the passes of a frame take on the GPU, using timestamp queries.
+///
+/// Every frame in flight has its own range of queries. The results of a frame are read back after
+/// its fence signaled, right before the frame's objects are reused, so the timings lag behind by
+/// a few frames. If the queue does not support timestamps, no timings are reported.
+pub struct GpuTimer {
+    query_pool: Option<vk::QueryPool>,
+    /// Nanoseconds per timestamp tick
+    timestamp_period: f64,
+    /// Only the lower bits of a timestamp are valid
+    timestamp_mask: u64,
+    /// Names of the passes recorded into each frame's query range, in order.
+    recorded_passes: Vec<Vec<&'static str>>,
+    /// Query index of the end timestamp of the pass that is currently recorded, if it is timed.
+    open_pass_end_query: Option<u32>,
+    /// Duration of each pass in milliseconds
+    timings: HashMap<&'static str, f64>,
+}
+
+impl GpuTimer {
+    pub unsafe fn new(
+        device: &Device,
+        timestamp_period: f32,
+        timestamp_valid_bits: u32,
+        frames_in_flight: usize,
+    ) -> Result<Self> {
+        let query_pool = if timestamp_valid_bits == 0 || timestamp_period == 0.0 {
+            info!("GPU timestamps are not supported, GPU timings are not available");
+            None
+        } else {
+            let create_info = vk::QueryPoolCreateInfo::builder()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(frames_in_flight as u32 * MAX_PASSES * 2);
+            Some(
+                device
+                    .create_query_pool(&create_info, None)
+                    .context("Could not create timestamp query pool.")?,
+            )
+        };
+        Ok(GpuTimer {
+            query_pool,
+            timestamp_period: timestamp_period as f64,
+            timestamp_mask: if timestamp_valid_bits >= 64 {
+                u64::MAX
+            } else {
+                (1 << timestamp_valid_bits) - 1
+            },
+            recorded_passes: vec![vec![]; frames_in_flight],
+            open_pass_end_query: None,
+            timings: HashMap::new(),
+        })
+    }
+
+    /// Duration of each pass in milliseconds, keyed by the pass name.
+    pub fn timings(&self) -> &HashMap<&'static str, f64> {
+        &self.timings
+    }
+
+    /// Reads back the timings of the frame that last used the given frame slot. The frame's fence
+    /// must have signaled.
+    pub unsafe fn read_results(&mut self, device: &Device, frame: usize) -> Result<()> {
+        let query_pool = match self.query_pool {
+            Some(query_pool) => query_pool,
+            None => return Ok(()),
+        };
+        let passes = &self.recorded_passes[frame];
+        if passes.is_empty() {
+            return Ok(());
+        }
+        let mut timestamps = vec![0u64; passes.len() * 2];
+        device
+            .get_query_pool_results(
+                query_pool,
+                first_query(frame),
+                timestamps.len() as u32,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+            .context("Failed to read the GPU timestamps")?;
+        for (name, timestamps) in passes.iter().zip(timestamps.chunks_exact(2)) {
+            let ticks = (timestamps[1] & self.timestamp_mask)
+                .wrapping_sub(timestamps[0] & self.timestamp_mask)
+                & self.timestamp_mask;
+            self.timings
+                .insert(name, ticks as f64 * self.timestamp_period / 1_000_000.0);
+        }
+        Ok(())
+    }
+
+    /// Resets the frame's queries, must be recorded before any pass of the frame.
+    pub unsafe fn begin_frame(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) {
+        self.recorded_passes[frame].clear();
+        if let Some(query_pool) = self.query_pool {
+            device.cmd_reset_query_pool(
+                command_buffer,
+                query_pool,
+                first_query(frame),
+                MAX_PASSES * 2,
+            );
+        }
+    }
+
+    /// Records the start of a pass. Passes beyond [MAX_PASSES] are not timed.
+    pub unsafe fn begin_pass(
+        &mut self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        name: &'static str,
+    ) {
+        let passes = &mut self.recorded_passes[frame];
+        self.open_pass_end_query = None;
+        if let Some(query_pool) = self.query_pool {
+            if passes.len() < MAX_PASSES as usize {
+                let start_query = first_query(frame) + passes.len() as u32 * 2;
+                device.cmd_write_timestamp(
+                    command_buffer,
+                    vk::PipelineStageFlags::TOP_OF_PIPE,
+                    query_pool,
+                    start_query,
+                );
+                passes.push(name);
+                self.open_pass_end_query = Some(start_query + 1);
+            }
+        }
+    }
+
+    /// Records the end of the pass that was started last.
+    pub unsafe fn end_pass(&mut self, device: &Device, command_buffer: vk::CommandBuffer) {
+        if let (Some(query_pool), Some(end_query)) =
+            (self.query_pool, self.open_pass_end_query.take())
+        {
+            device.cmd_write_timestamp(
+                command_buffer,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                query_pool,
+                end_query,
+            );
+        }
+    }
+
+    pub unsafe fn destroy(&mut self, device: &Device) {
+        if let Some(query_pool) = self.query_pool.take() {
+            device.destroy_query_pool(query_pool, None);
+        }
+    }
+}
+
+/// Index of the first query of a frame slot.
+fn first_query(frame: usize) -> u32 {
+    frame as u32 * MAX_PASSES * 2
+}
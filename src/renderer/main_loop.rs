@@ -1,18 +1,77 @@
 use crate::renderer::debug::vulkan_debug_utils_callback;
+use crate::renderer::fps_counter::FpsCounter;
+use crate::renderer::gamepad::Gamepads;
+pub use crate::renderer::gamepad::{Axis, Button};
+use crate::renderer::gpu_timer::GpuTimer;
+use crate::rng::Rng;
+use anyhow::{Context, Result};
 use ash::extensions::{ext, khr};
-use ash::prelude::VkResult;
 use ash::{vk, Device, Entry, Instance};
-use log::{debug, error, info, log, trace};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::ffi::{CStr, CString};
-use std::ptr::drop_in_place;
-use winit::dpi::LogicalSize;
-use winit::event::{Event, WindowEvent};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use winit::dpi::{LogicalSize, PhysicalPosition};
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Window, WindowBuilder};
+use winit::window::{Icon, Window, WindowBuilder};
 
 pub struct RenderLoopSettings {
-    window_title: String,
-    window_size: (u32, u32),
+    pub window_title: String,
+    pub window_size: (u32, u32),
+    /// The window can't be resized below this size. The window manager enforces it, a window
+    /// that is minimized anyway keeps its old swapchain until it is restored.
+    pub min_window_size: Option<(u32, u32)>,
+    /// Position of the window's top left corner in physical pixels, in the coordinates spanning
+    /// all monitors. Can be overridden with the environment variable `RACING_WINDOW_POSITION=x,y`.
+    /// If it is not on any monitor, the window is centered instead.
+    pub window_position: Option<(i32, i32)>,
+    /// Open the window in the center of the primary monitor, unless a position is given. Otherwise
+    /// the operating system decides where the window opens.
+    pub center_window: bool,
+    /// PNG image shown as the window icon, e.g. in the taskbar. If it can't be loaded, the
+    /// window is created without an icon.
+    pub icon: Option<PathBuf>,
+    /// Windowing system to use on Linux and the BSDs, ignored on other platforms. If `None`, winit
+    /// picks one, which can be overridden with the environment variable `WINIT_UNIX_BACKEND`
+    /// (`x11` or `wayland`).
+    pub unix_backend: Option<UnixBackend>,
+    /// Use the first compatible GPU whose name contains this string. If no such GPU is
+    /// available, the best available GPU is selected instead.
+    pub preferred_device: Option<String>,
+    /// Use the GPU at this position in the list returned by [list_physical_devices], e.g. to tell
+    /// two identical GPUs apart. Takes precedence over `preferred_device`. If the GPU is not
+    /// compatible, the selection falls back like for `preferred_device`.
+    pub preferred_device_index: Option<usize>,
+    /// Wait for the vertical blank before presenting a frame. Turn this off to render as fast as
+    /// possible, e.g. for benchmarking.
+    pub vsync: bool,
+    /// Append the current frame rate to the window title.
+    pub show_fps_in_title: bool,
+    /// Enable the vulkan validation layer, if it is installed. Can also be enabled by setting
+    /// the environment variable `RACING_VALIDATION=1`.
+    pub validation: bool,
+    /// Validation messages below this severity are not reported.
+    pub validation_severity: ValidationSeverity,
+    /// Pressing this key closes the window.
+    pub exit_key: Option<VirtualKeyCode>,
+    /// Upper limit for the frame rate. Uncapped if `None`.
+    pub max_fps: Option<u32>,
+    /// Stop updating and drawing while the window is not focused.
+    pub pause_on_focus_loss: bool,
+    /// Seed of the random number generators available through [UpdateContext::rng] and
+    /// [DrawContext::rng]. A seed taken from the clock is used if `None`, it is logged so that a
+    /// run can be reproduced.
+    pub seed: Option<u64>,
+    /// How many times per second [App::update] is called.
+    pub update_rate: u32,
+    /// How many frames the CPU may prepare while the GPU is still rendering earlier ones.
+    pub frames_in_flight: usize,
+    /// Number of images in the swapchain, e.g. 3 for triple buffering. Clamped to what the surface
+    /// supports. Defaults to one more than the minimum the surface requires.
+    pub swapchain_image_count: Option<u32>,
 }
 
 impl Default for RenderLoopSettings {
@@ -20,101 +79,1134 @@ impl Default for RenderLoopSettings {
         RenderLoopSettings {
             window_title: "".to_string(),
             window_size: (500, 500),
+            min_window_size: Some((320, 240)),
+            window_position: None,
+            center_window: true,
+            icon: None,
+            unix_backend: None,
+            preferred_device: None,
+            preferred_device_index: None,
+            vsync: true,
+            show_fps_in_title: false,
+            validation: cfg!(feature = "validation"),
+            validation_severity: ValidationSeverity::Warning,
+            exit_key: Some(VirtualKeyCode::Escape),
+            max_fps: None,
+            pause_on_focus_loss: false,
+            seed: None,
+            update_rate: 60,
+            frames_in_flight: 2,
+            swapchain_image_count: None,
         }
     }
 }
 
-pub struct DrawContext {}
+/// Windowing systems on Linux and the BSDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnixBackend {
+    X11,
+    Wayland,
+}
+
+/// Minimum severity of the messages reported by the validation layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValidationSeverity {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+impl ValidationSeverity {
+    /// The flags for this severity and all severities above it.
+    fn flags(self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        [
+            (
+                ValidationSeverity::Verbose,
+                vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            ),
+            (
+                ValidationSeverity::Info,
+                vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            ),
+            (
+                ValidationSeverity::Warning,
+                vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            ),
+            (
+                ValidationSeverity::Error,
+                vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            ),
+        ]
+        .into_iter()
+        .filter(|(severity, _)| *severity >= self)
+        .fold(
+            vk::DebugUtilsMessageSeverityFlagsEXT::empty(),
+            |flags, (_, flag)| flags | flag,
+        )
+    }
+}
+
+/// Color the screen is cleared to at the start of each frame.
+const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// How often the frame rate in the window title is refreshed.
+const FPS_TITLE_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Time the CPU spends in the phases of a frame, as a rolling average over the last frames.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuTimings {
+    /// Time spent in [App::update], for all updates of a frame together.
+    pub update: Duration,
+    /// Time spent in [App::draw].
+    pub draw: Duration,
+    /// Time spent recording, submitting and presenting the frame.
+    pub present: Duration,
+}
+
+impl CpuTimings {
+    /// Weight of a new sample in the rolling average
+    const SMOOTHING: f64 = 0.1;
+
+    fn add_sample(&mut self, sample: CpuTimings) {
+        let blend = |average: Duration, sample: Duration| {
+            average.mul_f64(1.0 - Self::SMOOTHING) + sample.mul_f64(Self::SMOOTHING)
+        };
+        self.update = blend(self.update, sample.update);
+        self.draw = blend(self.draw, sample.draw);
+        self.present = blend(self.present, sample.present);
+    }
+}
+
+pub struct UpdateContext<'a> {
+    rng: &'a mut Rng,
+}
+
+impl UpdateContext<'_> {
+    /// Random number generator for the simulation, seeded from [RenderLoopSettings::seed]. It is
+    /// only advanced by updates, so the same seed gives the same simulation at any frame rate.
+    pub fn rng(&mut self) -> &mut Rng {
+        self.rng
+    }
+}
+
+pub struct DrawContext<'a> {
+    fps: f32,
+    cpu_timings: CpuTimings,
+    gpu_timings: &'a HashMap<&'static str, f64>,
+    enabled_extensions: &'a [String],
+    enabled_device_extensions: &'a [String],
+    gamepads: &'a Gamepads,
+    window: &'a Window,
+    cursor_grab: &'a mut bool,
+    rng: &'a mut Rng,
+}
+
+impl DrawContext<'_> {
+    /// Frames per second, averaged over the last second.
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    /// How long the phases of the previous frames took on the CPU.
+    pub fn cpu_timings(&self) -> CpuTimings {
+        self.cpu_timings
+    }
+
+    /// How long each render pass took on the GPU in milliseconds, keyed by the pass name. These
+    /// are measured a few frames behind. Empty if the GPU does not support timestamps.
+    pub fn gpu_timings(&self) -> &HashMap<&'static str, f64> {
+        self.gpu_timings
+    }
+
+    /// Random number generator for cosmetic effects, seeded from [RenderLoopSettings::seed].
+    /// How often it is used depends on the frame rate, so don't use it for the simulation, use
+    /// [UpdateContext::rng] instead.
+    pub fn rng(&mut self) -> &mut Rng {
+        self.rng
+    }
+
+    /// Names of the vulkan instance extensions the renderer enabled, e.g. for bug reports.
+    pub fn enabled_extensions(&self) -> &[String] {
+        self.enabled_extensions
+    }
+
+    /// Names of the vulkan device extensions the renderer enabled, e.g. for bug reports.
+    pub fn enabled_device_extensions(&self) -> &[String] {
+        self.enabled_device_extensions
+    }
+
+    /// Ratio of physical pixels to logical pixels of the window. The framebuffer is sized in
+    /// physical pixels, divide by this to lay out HUD elements in logical pixels.
+    pub fn scale_factor(&self) -> f64 {
+        self.window.scale_factor()
+    }
+
+    /// Locks the cursor to the window, e.g. for a mouse-look camera. The grab is restored
+    /// automatically when the window regains focus.
+    pub fn set_cursor_grab(&mut self, grab: bool) {
+        *self.cursor_grab = grab;
+        grab_cursor(self.window, grab);
+    }
+
+    /// Shows or hides the cursor while it is over the window.
+    pub fn set_cursor_visible(&self, visible: bool) {
+        self.window.set_cursor_visible(visible);
+    }
+
+    /// Position of an analog axis of the active gamepad, between -1 and 1. Returns 0 if no
+    /// gamepad is connected, or if the game was built without the `gamepad` feature.
+    pub fn gamepad_axis(&self, axis: Axis) -> f32 {
+        self.gamepads.axis(axis)
+    }
+
+    /// Whether a button of the active gamepad is pressed.
+    pub fn gamepad_button(&self, button: Button) -> bool {
+        self.gamepads.button(button)
+    }
+
+    /// How far an analog button (like the triggers) of the active gamepad is pressed, between
+    /// 0 and 1.
+    pub fn gamepad_button_value(&self, button: Button) -> f32 {
+        self.gamepads.button_value(button)
+    }
+}
+
+/// Grabbing the cursor is not supported on every platform, so a failure is not fatal.
+fn grab_cursor(window: &Window, grab: bool) {
+    if let Err(err) = window.set_cursor_grab(grab) {
+        warn!("Could not change the cursor grab: {}", err);
+    }
+}
+
+/// Upper limit for the time simulated by [App::update] in a single frame, so that a long stall
+/// does not have to be caught up with a huge number of updates.
+const MAX_UPDATE_CATCH_UP: Duration = Duration::from_millis(250);
 
 pub trait App {
+    /// Advances the simulation by one fixed time step. Called at [RenderLoopSettings::update_rate]
+    /// times per second, independent of the frame rate.
+    fn update(&mut self, _dt: Duration, _context: &mut UpdateContext) {}
+
     fn draw(&mut self, context: &mut DrawContext);
+
+    /// Called after the window was resized, with the new framebuffer size in pixels.
+    fn resized(&mut self, _width: u32, _height: u32) {}
+
+    /// Called when the user asks to close the window. Return `false` to keep it open.
+    fn on_close_requested(&mut self) -> bool {
+        true
+    }
+
+    /// Called when a file is dropped onto the window.
+    fn on_file_dropped(&mut self, _path: PathBuf) {}
+
+    /// Called when a file is dragged over the window, e.g. to show a drop indicator. Followed by
+    /// [App::on_file_dropped] or [App::on_file_hover_cancelled].
+    fn on_file_hovered(&mut self, _path: PathBuf) {}
+
+    /// Called when a file that was dragged over the window leaves it without being dropped.
+    fn on_file_hover_cancelled(&mut self) {}
+
+    /// Called with every window event before the renderer handles it. Return `true` to mark the
+    /// event as consumed, the renderer then skips its own handling of it.
+    fn on_window_event(&mut self, _event: &WindowEvent) -> bool {
+        false
+    }
+
+    /// Called once right before the main loop exits, while the renderer is still alive.
+    fn on_exit(&mut self) {}
 }
 
 /// Main loop, initializes vulkan, opens a window and starts drawing.
 ///
-/// Must run on main thread. Never returns.
-/// CAUTION: Since the main loop hijacks the main thread and never returns, variables living on the
-/// stack will not be dropped when the application exits. Anything that needs to be cleaned up
-/// should be owned by the [app] object.
-pub fn main_loop(settings: RenderLoopSettings, mut app: impl App + 'static) -> ! {
+/// Must run on main thread. Only returns if the initialization failed, everything that was
+/// created up to that point is cleaned up again.
+/// CAUTION: Once the initialization succeeded, the main loop hijacks the main thread and does not
+/// return anymore, so variables living on the stack will not be dropped when the application
+/// exits. Anything that needs to be cleaned up should be owned by the [app] object.
+pub fn main_loop(settings: RenderLoopSettings, app: impl App + 'static) -> Result<Infallible> {
+    // window
+    let event_loop = create_event_loop(&settings);
+    let mut window_builder = WindowBuilder::new()
+        .with_title(&settings.window_title)
+        .with_inner_size(LogicalSize::new(
+            settings.window_size.0,
+            settings.window_size.1,
+        ))
+        .with_window_icon(settings.icon.as_deref().and_then(|path| {
+            load_window_icon(path)
+                .map_err(|err| warn!("Could not load the window icon: {:#}", err))
+                .ok()
+        }));
+    if let Some((width, height)) = settings.min_window_size {
+        window_builder = window_builder.with_min_inner_size(LogicalSize::new(width, height));
+    }
+    let window = window_builder
+        .build(&event_loop)
+        .context("Could not create window")?;
+    if let Some((x, y)) = window_position_from_env().or(settings.window_position) {
+        let on_a_monitor = window.available_monitors().any(|monitor| {
+            let position = monitor.position();
+            let size = monitor.size();
+            (position.x..position.x + size.width as i32).contains(&x)
+                && (position.y..position.y + size.height as i32).contains(&y)
+        });
+        if on_a_monitor {
+            window.set_outer_position(PhysicalPosition::new(x, y));
+        } else {
+            warn!(
+                "Window position ({}, {}) is not on any monitor, centering the window instead",
+                x, y
+            );
+            center_window(&window);
+        }
+    } else if settings.center_window {
+        center_window(&window);
+    }
+
+    // Vulkan
+    let vulkan = unsafe { Vulkan::new(&window, &settings)? };
+
+    // todo continue tutorial here https://hoj-senna.github.io/ashen-aetna/text/009_Pipelines_Renderpasses.html
+    // https://github.com/ash-rs/ash/blob/master/examples/src/lib.rs
+
+    run(event_loop, window, vulkan, settings, app)
+}
+
+/// Kind of a GPU, in the order in which they are preferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuType {
+    Discrete,
+    Integrated,
+    Virtual,
+    Cpu,
+    Other,
+}
+
+/// A GPU as listed by [list_physical_devices].
+#[derive(Debug, Clone)]
+pub struct GpuInfo {
+    /// Can be used as [RenderLoopSettings::preferred_device_index]. Unlike the name, this tells
+    /// identical GPUs apart, but it may change when GPUs or drivers are added or removed.
+    pub index: usize,
+    /// Can be used as [RenderLoopSettings::preferred_device].
+    pub name: String,
+    pub gpu_type: GpuType,
+    /// PCI vendor id, for display only. GPUs can't be selected by it.
+    pub vendor_id: u32,
+    /// Vendor specific device id, for display only. GPUs can't be selected by it.
+    pub device_id: u32,
+}
+
+/// Lists all GPUs with a vulkan driver, e.g. to let the user pick one in a settings menu. Works
+/// without a window, so it can be called before [main_loop].
+///
+/// This does not check whether the GPUs can actually present to a window.
+pub fn list_physical_devices() -> Result<Vec<GpuInfo>> {
     unsafe {
-        // window
-        let event_loop = EventLoop::new();
-        let window = WindowBuilder::new()
-            .with_title(&settings.window_title)
-            .with_inner_size(LogicalSize::new(
-                settings.window_size.0,
-                settings.window_size.1,
-            ))
-            .build(&event_loop)
-            .expect("Could not create window");
-
-        // Vulkan
-        let entry = Entry::load().expect("Failed to load the vulkan library.");
+        let entry = Entry::load()
+            .context("Failed to load the vulkan library; is your graphics driver installed?")?;
+        let (portability_extensions, flags) = portability_enumeration(&entry)?;
+        let p_enabled_extension_names = portability_extensions
+            .iter()
+            .map(|it| it.as_ptr())
+            .collect::<Vec<_>>();
+        let create_options = vk::InstanceCreateInfo {
+            p_application_info: &vk::ApplicationInfo {
+                api_version: vk::make_api_version(0, 1, 0, 0),
+                ..Default::default()
+            },
+            flags,
+            enabled_extension_count: p_enabled_extension_names.len() as u32,
+            pp_enabled_extension_names: p_enabled_extension_names.as_ptr(),
+            ..Default::default()
+        };
+        let instance = entry
+            .create_instance(&create_options, None)
+            .context("Failed to create the vulkan instance.")?;
+        let physical_devices = instance.enumerate_physical_devices();
+        let gpus = physical_devices.map(|physical_devices| {
+            physical_devices
+                .into_iter()
+                .enumerate()
+                .map(|(index, physical_device)| {
+                    let properties = instance.get_physical_device_properties(physical_device);
+                    GpuInfo {
+                        index,
+                        name: CStr::from_ptr(properties.device_name.as_ptr())
+                            .to_string_lossy()
+                            .into_owned(),
+                        gpu_type: match properties.device_type {
+                            vk::PhysicalDeviceType::DISCRETE_GPU => GpuType::Discrete,
+                            vk::PhysicalDeviceType::INTEGRATED_GPU => GpuType::Integrated,
+                            vk::PhysicalDeviceType::VIRTUAL_GPU => GpuType::Virtual,
+                            vk::PhysicalDeviceType::CPU => GpuType::Cpu,
+                            _ => GpuType::Other,
+                        },
+                        vendor_id: properties.vendor_id,
+                        device_id: properties.device_id,
+                    }
+                })
+                .collect()
+        });
+        // also clean up if listing failed
+        instance.destroy_instance(None);
+        gpus.context("Failed to list physical devices")
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn create_event_loop(settings: &RenderLoopSettings) -> EventLoop<()> {
+    use winit::platform::unix::{EventLoopExtUnix, EventLoopWindowTargetExtUnix};
+
+    let event_loop = match settings.unix_backend {
+        Some(UnixBackend::X11) => EventLoop::new_x11().unwrap_or_else(|err| {
+            warn!("X11 is not available, using the default backend: {}", err);
+            EventLoop::new()
+        }),
+        // winit panics if there is no wayland compositor, so check for one first
+        Some(UnixBackend::Wayland) if std::env::var_os("WAYLAND_DISPLAY").is_some() => {
+            EventLoop::new_wayland()
+        }
+        Some(UnixBackend::Wayland) => {
+            warn!("Wayland is not available, using the default backend");
+            EventLoop::new()
+        }
+        None => EventLoop::new(),
+    };
+    info!(
+        "Windowing backend: {}",
+        if event_loop.is_wayland() {
+            "Wayland"
+        } else {
+            "X11"
+        }
+    );
+    event_loop
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn create_event_loop(_settings: &RenderLoopSettings) -> EventLoop<()> {
+    EventLoop::new()
+}
+
+/// Reads `RACING_WINDOW_POSITION=x,y`.
+fn window_position_from_env() -> Option<(i32, i32)> {
+    let value = std::env::var("RACING_WINDOW_POSITION").ok()?;
+    let position = value
+        .split_once(',')
+        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)));
+    if position.is_none() {
+        warn!(
+            "Ignoring RACING_WINDOW_POSITION={}, expected two integers like 100,200",
+            value
+        );
+    }
+    position
+}
+
+/// Moves the window to the center of the primary monitor.
+fn center_window(window: &Window) {
+    match window
+        .primary_monitor()
+        .or_else(|| window.current_monitor())
+    {
+        Some(monitor) => {
+            let monitor_position = monitor.position();
+            let monitor_size = monitor.size();
+            let window_size = window.outer_size();
+            window.set_outer_position(PhysicalPosition::new(
+                monitor_position.x + (monitor_size.width as i32 - window_size.width as i32) / 2,
+                monitor_position.y + (monitor_size.height as i32 - window_size.height as i32) / 2,
+            ));
+        }
+        None => debug!("No monitor reported, the window can't be centered"),
+    }
+}
+
+fn load_window_icon(path: &Path) -> Result<Icon> {
+    let image = image::open(path)
+        .with_context(|| format!("Could not read {}", path.display()))?
+        .into_rgba8();
+    let (width, height) = image.dimensions();
+    Ok(Icon::from_rgba(image.into_raw(), width, height)?)
+}
+
+/// Runs the event loop on an initialized renderer. Never returns.
+fn run(
+    event_loop: EventLoop<()>,
+    window: Window,
+    mut vulkan: Vulkan,
+    settings: RenderLoopSettings,
+    mut app: impl App + 'static,
+) -> ! {
+    // frame rate
+    let mut fps_counter = FpsCounter::new();
+    let mut last_fps_title_update = Instant::now();
+    let min_frame_interval = settings
+        .max_fps
+        .map(|max_fps| Duration::from_secs_f64(1.0 / max_fps.max(1) as f64));
+    let mut next_frame_time = Instant::now();
+    let mut cpu_timings = CpuTimings::default();
+
+    // input
+    let mut gamepads = Gamepads::new();
+    let mut cursor_grab = false;
+    let mut paused = false;
+
+    // randomness
+    let seed = settings.seed.unwrap_or_else(Rng::seed_from_time);
+    info!("Random seed: {}", seed);
+    let mut update_rng = Rng::from_seed(seed);
+    let mut draw_rng = Rng::from_seed(seed.wrapping_add(1));
+
+    // fixed time step updates
+    let update_interval = Duration::from_secs_f64(1.0 / settings.update_rate.max(1) as f64);
+    let mut last_update_time = Instant::now();
+    let mut update_time_accumulator = Duration::ZERO;
+
+    // run event loop
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent { event, .. } if app.on_window_event(&event) => {}
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested if app.on_close_requested() => {
+                *control_flow = ControlFlow::Exit
+            }
+            // a minimized window has no area to draw on, keep the old swapchain
+            WindowEvent::Resized(size) if size.width != 0 && size.height != 0 => {
+                unsafe {
+                    vulkan
+                        .recreate_swapchain(&window, &settings)
+                        .expect("Failed to recreate the swapchain");
+                }
+                // the surface may not match the window size exactly, report what is rendered to
+                app.resized(
+                    vulkan.swapchain_extent.width,
+                    vulkan.swapchain_extent.height,
+                );
+            }
+            // the window only takes on the new size after this event, so the swapchain is
+            // recreated lazily before the next frame. The app hears about the new size from the
+            // `Resized` event that follows.
+            WindowEvent::ScaleFactorChanged { .. } => {
+                vulkan.recreate_swapchain = true;
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } if Some(key) == settings.exit_key && app.on_close_requested() => {
+                *control_flow = ControlFlow::Exit
+            }
+            WindowEvent::DroppedFile(path) => app.on_file_dropped(path),
+            WindowEvent::HoveredFile(path) => app.on_file_hovered(path),
+            WindowEvent::HoveredFileCancelled => app.on_file_hover_cancelled(),
+            WindowEvent::Focused(focused) => {
+                // most platforms release the grab when the window loses focus
+                if focused && cursor_grab {
+                    grab_cursor(&window, true);
+                }
+                if paused && focused {
+                    // don't catch up on the time spent paused
+                    last_update_time = Instant::now();
+                }
+                paused = settings.pause_on_focus_loss && !focused;
+            }
+            _ => {}
+        },
+        Event::MainEventsCleared => {
+            // wait until the next frame is due, if the frame rate is capped
+            if paused {
+                *control_flow = ControlFlow::Wait;
+            } else if Instant::now() < next_frame_time {
+                *control_flow = ControlFlow::WaitUntil(next_frame_time);
+            } else {
+                *control_flow = ControlFlow::Poll;
+                window.request_redraw();
+            }
+        }
+        Event::RedrawRequested(_) if !paused => {
+            let now = Instant::now();
+            if let Some(min_frame_interval) = min_frame_interval {
+                // measured from the start of this frame, so the time spent rendering counts
+                next_frame_time = now + min_frame_interval;
+            }
+            fps_counter.frame(now);
+            if settings.show_fps_in_title
+                && now.duration_since(last_fps_title_update) >= FPS_TITLE_UPDATE_INTERVAL
+            {
+                window.set_title(&format!(
+                    "{} — {:.0} fps",
+                    settings.window_title,
+                    fps_counter.fps()
+                ));
+                last_fps_title_update = now;
+            }
+
+            gamepads.poll();
+
+            // the leftover time is carried over to the next frame
+            let update_start = Instant::now();
+            update_time_accumulator = (update_time_accumulator
+                + now.duration_since(last_update_time))
+            .min(MAX_UPDATE_CATCH_UP);
+            last_update_time = now;
+            while update_time_accumulator >= update_interval {
+                app.update(
+                    update_interval,
+                    &mut UpdateContext {
+                        rng: &mut update_rng,
+                    },
+                );
+                update_time_accumulator -= update_interval;
+            }
+
+            let draw_start = Instant::now();
+            app.draw(&mut DrawContext {
+                fps: fps_counter.fps(),
+                cpu_timings,
+                gpu_timings: vulkan.gpu_timer.timings(),
+                enabled_extensions: &vulkan.enabled_extensions,
+                enabled_device_extensions: &vulkan.enabled_device_extensions,
+                gamepads: &gamepads,
+                window: &window,
+                cursor_grab: &mut cursor_grab,
+                rng: &mut draw_rng,
+            });
+            let present_start = Instant::now();
+            unsafe {
+                vulkan
+                    .draw_frame(&window, &settings)
+                    .expect("Failed to draw the frame");
+            }
+            cpu_timings.add_sample(CpuTimings {
+                update: draw_start - update_start,
+                draw: present_start - draw_start,
+                present: present_start.elapsed(),
+            });
+        }
+        Event::LoopDestroyed => {
+            app.on_exit();
+            unsafe { vulkan.shutdown() };
+        }
+        _ => {}
+    });
+}
+
+/// The vulkan objects that live as long as the window.
+struct Vulkan {
+    /// Keeps the vulkan library loaded
+    _entry: Entry,
+    instance: Instance,
+    enabled_extensions: Vec<String>,
+    debug_utils: Option<(ext::DebugUtils, vk::DebugUtilsMessengerEXT)>,
+    ext_surface: khr::Surface,
+    surface: vk::SurfaceKHR,
+    physical_device: vk::PhysicalDevice,
+    device: Device,
+    enabled_device_extensions: Vec<String>,
+    queues: Queues,
+    ext_swapchain: khr::Swapchain,
+    swapchain: vk::SwapchainKHR,
+    swapchain_image_views: Vec<vk::ImageView>,
+    swapchain_extent: vk::Extent2D,
+    /// Set when the swapchain no longer matches the surface, it is recreated before the next frame.
+    recreate_swapchain: bool,
+    render_pass: vk::RenderPass,
+    framebuffers: Vec<vk::Framebuffer>,
+    command_pool: vk::CommandPool,
+    frames: Vec<FrameInFlight>,
+    /// Index into `frames` of the frame that is drawn next.
+    current_frame: usize,
+    /// For each swapchain image, the fence of the frame that last rendered to it.
+    images_in_flight: Vec<vk::Fence>,
+    /// For each swapchain image, signaled when rendering to it finished, for the presentation to
+    /// wait on. These belong to the image rather than to the frame in flight, because the frame's
+    /// fence does not tell when the presentation stopped waiting on the semaphore.
+    render_finished_semaphores: Vec<vk::Semaphore>,
+    gpu_timer: GpuTimer,
+}
+
+/// Destroys the vulkan objects created so far when the initialization fails half way, in the
+/// reverse order of their creation.
+#[derive(Default)]
+struct CleanupOnError(Vec<Box<dyn FnOnce()>>);
+
+impl CleanupOnError {
+    /// Registers how to destroy an object that was just created.
+    fn push(&mut self, destroy: impl FnOnce() + 'static) {
+        self.0.push(Box::new(destroy));
+    }
+
+    /// The initialization succeeded, the objects must not be destroyed.
+    fn disarm(mut self) {
+        self.0.clear();
+    }
+}
+
+impl Drop for CleanupOnError {
+    fn drop(&mut self) {
+        while let Some(destroy) = self.0.pop() {
+            destroy();
+        }
+    }
+}
+
+/// The per-frame objects, so that the CPU can record a frame while the GPU renders earlier ones.
+#[derive(Clone, Copy)]
+struct FrameInFlight {
+    command_buffer: vk::CommandBuffer,
+    image_available_semaphore: vk::Semaphore,
+    in_flight_fence: vk::Fence,
+}
+
+impl Vulkan {
+    unsafe fn new(window: &Window, settings: &RenderLoopSettings) -> Result<Self> {
+        let entry = Entry::load()
+            .context("Failed to load the vulkan library; is your graphics driver installed?")?;
         let mut debug_utils = None;
-        let instance = create_instance(&entry, &window, &mut debug_utils);
+        let validation =
+            settings.validation || std::env::var("RACING_VALIDATION").is_ok_and(|it| it == "1");
+        let (instance, enabled_extensions) = create_instance(
+            &entry,
+            window,
+            validation,
+            settings.validation_severity,
+            &mut debug_utils,
+        )?;
+        // if any of the following steps fails, everything created so far is destroyed again
+        let mut cleanup = CleanupOnError::default();
+        cleanup.push({
+            let instance = instance.clone();
+            let debug_utils = debug_utils.clone();
+            move || {
+                if let Some((debug_utils, messenger)) = debug_utils {
+                    debug_utils.destroy_debug_utils_messenger(messenger, None);
+                }
+                instance.destroy_instance(None);
+            }
+        });
 
         // Instance extensions
         let ext_surface = khr::Surface::new(&entry, &instance);
 
         // surface
-        let mut surface = ash_window::create_surface(&entry, &instance, &window, None)
-            .expect("Could not create surface.");
+        let surface = ash_window::create_surface(&entry, &instance, window, None)
+            .context("Could not create surface.")?;
+        cleanup.push({
+            let ext_surface = ext_surface.clone();
+            move || ext_surface.destroy_surface(surface, None)
+        });
 
         // Device
-        let (physical_device, device, queue) = create_device(&instance, &ext_surface, &surface);
+        let (physical_device, device, queues, enabled_device_extensions) = create_device(
+            &instance,
+            &ext_surface,
+            &surface,
+            settings.preferred_device.as_deref(),
+            settings.preferred_device_index,
+        )?;
+        cleanup.push({
+            let device = device.clone();
+            move || device.destroy_device(None)
+        });
 
         // device extensions
         let ext_swapchain = khr::Swapchain::new(&instance, &device);
 
         // Swapchain
-        let (swapchain, swapchain_image_views) = create_swapchain(
-            physical_device,
+        let (swapchain, swapchain_image_views, swapchain_format, swapchain_extent) =
+            create_swapchain(
+                physical_device,
+                &device,
+                surface,
+                &ext_swapchain,
+                &ext_surface,
+                window,
+                settings,
+            )?;
+        cleanup.push({
+            let device = device.clone();
+            let ext_swapchain = ext_swapchain.clone();
+            let swapchain_image_views = swapchain_image_views.clone();
+            move || destroy_swapchain(&device, &ext_swapchain, swapchain, &swapchain_image_views)
+        });
+
+        // render pass and framebuffers
+        let render_pass = create_render_pass(&device, swapchain_format.format)?;
+        cleanup.push({
+            let device = device.clone();
+            move || device.destroy_render_pass(render_pass, None)
+        });
+        let framebuffers = create_framebuffers(
             &device,
+            render_pass,
+            &swapchain_image_views,
+            swapchain_extent,
+        )?;
+        cleanup.push({
+            let device = device.clone();
+            let framebuffers = framebuffers.clone();
+            move || {
+                for framebuffer in framebuffers {
+                    device.destroy_framebuffer(framebuffer, None);
+                }
+            }
+        });
+
+        // command buffer
+        let command_pool = device
+            .create_command_pool(
+                &vk::CommandPoolCreateInfo::builder()
+                    .queue_family_index(queues.graphics_family_index)
+                    .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
+                None,
+            )
+            .context("Could not create command pool.")?;
+        cleanup.push({
+            let device = device.clone();
+            move || device.destroy_command_pool(command_pool, None)
+        });
+        let frames_in_flight = settings.frames_in_flight.max(1);
+        let command_buffers = device
+            .allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::builder()
+                    .command_pool(command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(frames_in_flight as u32),
+            )
+            .context("Could not allocate command buffers.")?;
+
+        // synchronization
+        let mut frames = Vec::with_capacity(frames_in_flight);
+        for command_buffer in command_buffers {
+            let image_available_semaphore = device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                .context("Could not create semaphore.")?;
+            cleanup.push({
+                let device = device.clone();
+                move || device.destroy_semaphore(image_available_semaphore, None)
+            });
+            let in_flight_fence = device
+                .create_fence(
+                    &vk::FenceCreateInfo::builder().flags(vk::FenceCreateFlags::SIGNALED),
+                    None,
+                )
+                .context("Could not create fence.")?;
+            cleanup.push({
+                let device = device.clone();
+                move || device.destroy_fence(in_flight_fence, None)
+            });
+            frames.push(FrameInFlight {
+                command_buffer,
+                image_available_semaphore,
+                in_flight_fence,
+            });
+        }
+        let images_in_flight = vec![vk::Fence::null(); swapchain_image_views.len()];
+        let render_finished_semaphores = create_semaphores(&device, swapchain_image_views.len())?;
+        cleanup.push({
+            let device = device.clone();
+            let render_finished_semaphores = render_finished_semaphores.clone();
+            move || destroy_semaphores(&device, &render_finished_semaphores)
+        });
+
+        // profiling
+        let timestamp_period = instance
+            .get_physical_device_properties(physical_device)
+            .limits
+            .timestamp_period;
+        let timestamp_valid_bits = instance
+            .get_physical_device_queue_family_properties(physical_device)
+            [queues.graphics_family_index as usize]
+            .timestamp_valid_bits;
+        let gpu_timer = GpuTimer::new(
+            &device,
+            timestamp_period,
+            timestamp_valid_bits,
+            frames_in_flight,
+        )?;
+
+        // from here on, the objects are destroyed by `shutdown`
+        cleanup.disarm();
+        Ok(Vulkan {
+            _entry: entry,
+            instance,
+            enabled_extensions,
+            debug_utils,
+            ext_surface,
             surface,
-            &ext_swapchain,
-            &ext_surface,
-            &window,
-        );
+            physical_device,
+            device,
+            enabled_device_extensions,
+            queues,
+            ext_swapchain,
+            swapchain,
+            swapchain_image_views,
+            swapchain_extent,
+            recreate_swapchain: false,
+            render_pass,
+            framebuffers,
+            command_pool,
+            frames,
+            current_frame: 0,
+            images_in_flight,
+            render_finished_semaphores,
+            gpu_timer,
+        })
+    }
 
-        // todo continue tutorial here https://hoj-senna.github.io/ashen-aetna/text/009_Pipelines_Renderpasses.html
-        // https://github.com/ash-rs/ash/blob/master/examples/src/lib.rs
+    /// Renders and presents one frame.
+    ///
+    /// The frame goes through these steps:
+    /// 1. If the last frame flagged the swapchain as out of date, it is recreated first.
+    /// 2. Wait until the GPU finished the frame that last used the current frame's objects, so its
+    ///    command buffer can be reused.
+    /// 3. Acquire the next swapchain image. If the swapchain is out of date, the frame is skipped
+    ///    and the swapchain gets recreated on the next call. If it is suboptimal, the frame is
+    ///    still drawn, but the swapchain is recreated afterwards.
+    /// 4. Record and submit the command buffer.
+    /// 5. Present the image, again flagging the swapchain for recreation if it is out of date or
+    ///    suboptimal.
+    unsafe fn draw_frame(&mut self, window: &Window, settings: &RenderLoopSettings) -> Result<()> {
+        // a minimized window has no area to draw on
+        let window_size = window.inner_size();
+        if window_size.width == 0 || window_size.height == 0 {
+            return Ok(());
+        }
 
-        // run event loop
-        event_loop.run(move |event, _, control_flow| match event {
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                _ => {}
-            },
-            Event::MainEventsCleared => window.request_redraw(),
-            Event::RedrawRequested(_) => {
-                app.draw(&mut DrawContext {});
-                *control_flow = ControlFlow::Exit; // todo remove
+        if self.recreate_swapchain {
+            self.recreate_swapchain(window, settings)?;
+        }
+
+        let frame = self.frames[self.current_frame];
+        self.device
+            .wait_for_fences(&[frame.in_flight_fence], true, u64::MAX)
+            .context("Failed to wait for the previous frame")?;
+        self.gpu_timer
+            .read_results(&self.device, self.current_frame)?;
+
+        let image_index = match self.ext_swapchain.acquire_next_image(
+            self.swapchain,
+            u64::MAX,
+            frame.image_available_semaphore,
+            vk::Fence::null(),
+        ) {
+            Ok((image_index, suboptimal)) => {
+                self.recreate_swapchain |= suboptimal;
+                image_index
             }
-            Event::LoopDestroyed => {
-                shutdown(
-                    &entry,
-                    &instance,
-                    &mut debug_utils,
-                    &device,
-                    surface,
-                    swapchain,
-                    &swapchain_image_views,
-                    &ext_swapchain,
-                    &ext_surface,
-                );
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.recreate_swapchain = true;
+                return Ok(());
             }
-            _ => {}
-        });
+            Err(err) => return Err(err).context("Failed to acquire the next swapchain image"),
+        };
+
+        // the swapchain may hand out an image that an older frame is still rendering to
+        let image_in_flight = self.images_in_flight[image_index as usize];
+        if image_in_flight != vk::Fence::null() {
+            self.device
+                .wait_for_fences(&[image_in_flight], true, u64::MAX)
+                .context("Failed to wait for the swapchain image")?;
+        }
+        self.images_in_flight[image_index as usize] = frame.in_flight_fence;
+
+        self.device
+            .reset_fences(&[frame.in_flight_fence])
+            .context("Failed to reset fence")?;
+        self.record_command_buffer(frame.command_buffer, image_index)?;
+
+        let wait_semaphores = [frame.image_available_semaphore];
+        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let command_buffers = [frame.command_buffer];
+        let signal_semaphores = [self.render_finished_semaphores[image_index as usize]];
+        let submit_info = vk::SubmitInfo::builder()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores)
+            .build();
+        self.device
+            .queue_submit(self.queues.graphics, &[submit_info], frame.in_flight_fence)
+            .context("Failed to submit the command buffer")?;
+
+        let swapchains = [self.swapchain];
+        let image_indices = [image_index];
+        let present_info = vk::PresentInfoKHR::builder()
+            .wait_semaphores(&signal_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices)
+            .build();
+        self.current_frame = (self.current_frame + 1) % self.frames.len();
+        match self
+            .ext_swapchain
+            .queue_present(self.queues.graphics, &present_info)
+        {
+            Ok(suboptimal) => self.recreate_swapchain |= suboptimal,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.recreate_swapchain = true,
+            Err(err) => return Err(err).context("Failed to present the frame"),
+        }
+        Ok(())
+    }
+
+    /// Records the commands for drawing into the given swapchain image.
+    unsafe fn record_command_buffer(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        image_index: u32,
+    ) -> Result<()> {
+        self.device
+            .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+            .context("Failed to reset the command buffer")?;
+        self.device
+            .begin_command_buffer(
+                command_buffer,
+                &vk::CommandBufferBeginInfo::builder()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            )
+            .context("Failed to begin the command buffer")?;
+
+        let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffers[image_index as usize])
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.swapchain_extent,
+            })
+            .clear_values(&[vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: CLEAR_COLOR,
+                },
+            }]);
+        self.gpu_timer
+            .begin_frame(&self.device, command_buffer, self.current_frame);
+        self.gpu_timer
+            .begin_pass(&self.device, command_buffer, self.current_frame, "scene");
+        self.device.cmd_begin_render_pass(
+            command_buffer,
+            &render_pass_begin_info,
+            vk::SubpassContents::INLINE,
+        );
+        self.device.cmd_end_render_pass(command_buffer);
+        self.gpu_timer.end_pass(&self.device, command_buffer);
+
+        self.device
+            .end_command_buffer(command_buffer)
+            .context("Failed to end the command buffer")?;
+        Ok(())
+    }
+
+    /// Replaces the swapchain with one matching the current window size.
+    unsafe fn recreate_swapchain(
+        &mut self,
+        window: &Window,
+        settings: &RenderLoopSettings,
+    ) -> Result<()> {
+        self.device
+            .device_wait_idle()
+            .context("Failed to wait for the device to become idle")?;
+        for framebuffer in &self.framebuffers {
+            self.device.destroy_framebuffer(*framebuffer, None);
+        }
+        destroy_swapchain(
+            &self.device,
+            &self.ext_swapchain,
+            self.swapchain,
+            &self.swapchain_image_views,
+        );
+        // the render pass is kept, the surface format is assumed not to change
+        let swapchain_format;
+        (
+            self.swapchain,
+            self.swapchain_image_views,
+            swapchain_format,
+            self.swapchain_extent,
+        ) = create_swapchain(
+            self.physical_device,
+            &self.device,
+            self.surface,
+            &self.ext_swapchain,
+            &self.ext_surface,
+            window,
+            settings,
+        )?;
+        debug!(
+            "Recreated swapchain with format {:?}",
+            swapchain_format.format
+        );
+        self.framebuffers = create_framebuffers(
+            &self.device,
+            self.render_pass,
+            &self.swapchain_image_views,
+            self.swapchain_extent,
+        )?;
+        self.images_in_flight = vec![vk::Fence::null(); self.swapchain_image_views.len()];
+        // the number of images may have changed
+        destroy_semaphores(&self.device, &self.render_finished_semaphores);
+        self.render_finished_semaphores =
+            create_semaphores(&self.device, self.swapchain_image_views.len())?;
+        self.recreate_swapchain = false;
+        Ok(())
+    }
+
+    unsafe fn shutdown(&mut self) {
+        info!("Vulkan Shutdown");
+        self.device.device_wait_idle().ok();
+        for frame in &self.frames {
+            self.device.destroy_fence(frame.in_flight_fence, None);
+            self.device
+                .destroy_semaphore(frame.image_available_semaphore, None);
+        }
+        destroy_semaphores(&self.device, &self.render_finished_semaphores);
+        self.gpu_timer.destroy(&self.device);
+        self.device.destroy_command_pool(self.command_pool, None);
+        for framebuffer in &self.framebuffers {
+            self.device.destroy_framebuffer(*framebuffer, None);
+        }
+        self.device.destroy_render_pass(self.render_pass, None);
+        destroy_swapchain(
+            &self.device,
+            &self.ext_swapchain,
+            self.swapchain,
+            &self.swapchain_image_views,
+        );
+        self.ext_surface.destroy_surface(self.surface, None);
+        self.device.destroy_device(None);
+        if let Some((debug_utils, messenger)) = self.debug_utils.take() {
+            debug_utils.destroy_debug_utils_messenger(messenger, None)
+        }
+        self.instance.destroy_instance(None);
     }
 }
 
-/// Creates the vulkan instance. Panicks on failure.
+/// Creates the vulkan instance.
+///
+/// The validation layer is only enabled if it was requested and is available on the system.
 unsafe fn create_instance(
     entry: &Entry,
     window: &Window,
+    validation: bool,
+    validation_severity: ValidationSeverity,
     debug_utils_state: &mut Option<(ext::DebugUtils, vk::DebugUtilsMessengerEXT)>,
-) -> Instance {
+) -> Result<(Instance, Vec<String>)> {
     let mut create_options = vk::InstanceCreateInfo {
         p_application_info: &vk::ApplicationInfo {
             api_version: vk::make_api_version(0, 1, 0, 0),
@@ -127,18 +1219,45 @@ unsafe fn create_instance(
 
     // add validation, if requested
     let validation_layer_name = CString::new("VK_LAYER_KHRONOS_validation").unwrap();
-    if cfg!(feature = "validation") {
+    let validation = validation && {
+        let available = entry
+            .enumerate_instance_layer_properties()
+            .context("Failed to list the available instance layers")?
+            .iter()
+            .any(|it| CStr::from_ptr(it.layer_name.as_ptr()) == validation_layer_name.as_c_str());
+        if !available {
+            warn!("Validation was requested, but the validation layer is not installed");
+        }
+        available
+    };
+    info!(
+        "Validation layer {}",
+        if validation { "enabled" } else { "disabled" }
+    );
+    if validation {
         p_enabled_layer_names.push(validation_layer_name.as_ptr());
         p_enabled_extension_names.push(ext::DebugUtils::name().as_ptr());
     }
 
     // add support for drawing on the window
     let windowing_extensions = ash_window::enumerate_required_extensions(window)
-        .expect("enumerate_required_extensions failed");
+        .context("enumerate_required_extensions failed")?;
     p_enabled_extension_names.extend(windowing_extensions);
 
+    // list non-conformant implementations as well, like MoltenVK on macOS
+    let (portability_extensions, flags) = portability_enumeration(entry)?;
+    p_enabled_extension_names.extend(portability_extensions.iter().map(|it| it.as_ptr()));
+    create_options.flags |= flags;
+
     // extensions and layers
     let p_enabled_extension_names = p_enabled_extension_names; // drops the mut
+    let enabled_extensions = p_enabled_extension_names
+        .iter()
+        .map(|it| CStr::from_ptr(*it).to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    for extension_name in &enabled_extensions {
+        debug!("Enabled instance extension: {}", extension_name);
+    }
     let p_enabled_layer_names = p_enabled_layer_names;
     create_options.enabled_extension_count = p_enabled_extension_names.len() as u32;
     create_options.pp_enabled_extension_names = p_enabled_extension_names.as_ptr();
@@ -147,62 +1266,69 @@ unsafe fn create_instance(
 
     let instance = entry
         .create_instance(&create_options, None)
-        .expect("Failed to create the vulkan instance.");
+        .context("Failed to create the vulkan instance.")?;
 
     // configure validation layer
-    if cfg!(feature = "validation") {
+    if validation {
         let debug_utils = ext::DebugUtils::new(entry, &instance);
         let messenger_create_info = vk::DebugUtilsMessengerCreateInfoEXT {
-            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
-                | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-                | vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            message_severity: validation_severity.flags(),
             message_type: vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
                 | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
                 | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION,
             pfn_user_callback: Some(vulkan_debug_utils_callback),
             ..Default::default()
         };
-        let messenger = debug_utils
-            .create_debug_utils_messenger(&messenger_create_info, None)
-            .expect("Failed to install debug messenger for validation layer");
+        let messenger = match debug_utils.create_debug_utils_messenger(&messenger_create_info, None)
+        {
+            Ok(messenger) => messenger,
+            Err(err) => {
+                instance.destroy_instance(None);
+                return Err(err).context("Failed to install debug messenger for validation layer");
+            }
+        };
         *debug_utils_state = Some((debug_utils, messenger));
     }
-    instance
+    Ok((instance, enabled_extensions))
 }
 
-unsafe fn shutdown(
+/// The instance extensions and flags needed to enumerate GPUs that only implement a subset of
+/// vulkan. Nothing if the loader does not support portability enumeration.
+unsafe fn portability_enumeration(
     entry: &Entry,
-    instance: &Instance,
-    debug_utils_state: &mut Option<(ext::DebugUtils, vk::DebugUtilsMessengerEXT)>,
-    device: &Device,
-    surface: vk::SurfaceKHR,
-    swapchain: vk::SwapchainKHR,
-    swapchain_image_views: &Vec<vk::ImageView>,
-    ext_swapchain: &khr::Swapchain,
-    ext_surface: &khr::Surface,
-) {
-    info!("Vulkan Shutdown");
-    for image_view in swapchain_image_views {
-        device.destroy_image_view(*image_view, None);
+) -> Result<(Vec<&'static CStr>, vk::InstanceCreateFlags)> {
+    let extensions = entry
+        .enumerate_instance_extension_properties(None)
+        .context("Failed to list the available instance extensions")?;
+    let supported = |name: &CStr| {
+        extensions
+            .iter()
+            .any(|it| CStr::from_ptr(it.extension_name.as_ptr()) == name)
+    };
+    if !supported(vk::KhrPortabilityEnumerationFn::name()) {
+        return Ok((vec![], vk::InstanceCreateFlags::empty()));
     }
-    ext_swapchain.destroy_swapchain(swapchain, None);
-    ext_surface.destroy_surface(surface, None);
-    device.destroy_device(None);
-    if let Some((debug_utils, messenger)) = debug_utils_state.take() {
-        debug_utils.destroy_debug_utils_messenger(messenger, None)
+    let mut extension_names = vec![vk::KhrPortabilityEnumerationFn::name()];
+    // required by VK_KHR_portability_subset on vulkan 1.0
+    if supported(vk::KhrGetPhysicalDeviceProperties2Fn::name()) {
+        extension_names.push(vk::KhrGetPhysicalDeviceProperties2Fn::name());
     }
-    instance.destroy_instance(None);
+    Ok((
+        extension_names,
+        vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR,
+    ))
 }
 
 unsafe fn create_device(
     instance: &Instance,
     ext_surface: &khr::Surface,
     surface: &vk::SurfaceKHR,
-) -> (vk::PhysicalDevice, Device, vk::Queue) {
+    preferred_device: Option<&str>,
+    preferred_device_index: Option<usize>,
+) -> Result<(vk::PhysicalDevice, Device, Queues, Vec<String>)> {
     let physical_devices = instance
         .enumerate_physical_devices()
-        .expect("Failed to list physical devices");
+        .context("Failed to list physical devices")?;
 
     let required_extensions_names = [khr::Swapchain::name()];
 
@@ -215,13 +1341,13 @@ unsafe fn create_device(
                 instance.get_physical_device_queue_family_properties(physical_device);
             (physical_device, properties, queue_families)
         })
-        .filter_map(|(physical_device, properties, queue_families)| {
+        .map(|(physical_device, properties, queue_families)| {
             let device_name = CStr::from_ptr(properties.device_name.as_ptr()).to_string_lossy();
 
             // check device extensions
             let extensions = instance
                 .enumerate_device_extension_properties(physical_device)
-                .expect("Failed getting the supported device extensions");
+                .context("Failed getting the supported device extensions")?;
             for required_extension in required_extensions_names {
                 let supported = extensions
                     .iter()
@@ -232,40 +1358,40 @@ unsafe fn create_device(
                         device_name,
                         required_extension.to_string_lossy()
                     );
-                    return None;
+                    return Ok(None);
                 }
             }
 
             // look for a supported graphics queue family in this physical device
-            let queue_family_index =
-                queue_families
-                    .iter()
-                    .enumerate()
-                    .position(|(index, queue_family)| {
-                        let has_graphics =
-                            queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS);
-                        let supports_surface = ext_surface
-                            .get_physical_device_surface_support(
-                                physical_device,
-                                index as u32,
-                                *surface,
-                            )
-                            .expect("Failed to check for surface support");
-                        has_graphics && supports_surface
-                    });
+            let mut queue_family_index = None;
+            for (index, queue_family) in queue_families.iter().enumerate() {
+                let has_graphics = queue_family.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+                let supports_surface = ext_surface
+                    .get_physical_device_surface_support(physical_device, index as u32, *surface)
+                    .context("Failed to check for surface support")?;
+                if has_graphics && supports_surface {
+                    queue_family_index = Some(index);
+                    break;
+                }
+            }
 
             if let Some(queue_family_index) = queue_family_index {
                 debug!("Device '{}': Compatible", device_name);
-                Some((physical_device, properties, queue_family_index as u32))
+                Ok(Some((
+                    physical_device,
+                    properties,
+                    queue_family_index as u32,
+                )))
             } else {
                 debug!(
                     "Device '{}': Has no suitable graphics queue family",
                     device_name,
                 );
-                None
+                Ok(None)
             }
         })
-        .collect::<Vec<_>>();
+        .filter_map(Result::transpose)
+        .collect::<Result<Vec<_>>>()?;
 
     // select the best available device type
     ok_physical_devices.sort_by_key(|(_, properties, _)| match properties.device_type {
@@ -275,27 +1401,119 @@ unsafe fn create_device(
         vk::PhysicalDeviceType::CPU => 3,
         _ => 4,
     });
-    let (physical_device, properties, graphics_queue_family_index) = ok_physical_devices
-        .first()
-        .expect("There is no compatible physical device (GPU)");
-    info!(
-        "Using physical device: {}",
-        CStr::from_ptr(properties.device_name.as_ptr()).to_string_lossy()
-    );
+    let preferred_by_index = preferred_device_index.and_then(|preferred_index| {
+        let found = physical_devices.get(preferred_index).and_then(|&wanted| {
+            ok_physical_devices
+                .iter()
+                .find(|(physical_device, _, _)| *physical_device == wanted)
+        });
+        if found.is_none() {
+            warn!(
+                "Preferred device #{} is not available or not compatible, falling back to the default selection",
+                preferred_index
+            );
+        }
+        found
+    });
+    let preferred_by_name = || {
+        preferred_device.and_then(|preferred_name| {
+            let found = ok_physical_devices.iter().find(|(_, properties, _)| {
+                CStr::from_ptr(properties.device_name.as_ptr())
+                    .to_string_lossy()
+                    .contains(preferred_name)
+            });
+            if found.is_none() {
+                warn!(
+                    "Preferred device '{}' is not available or not compatible, falling back to the default selection",
+                    preferred_name
+                );
+            }
+            found
+        })
+    };
+    let (physical_device, properties, graphics_queue_family_index) = preferred_by_index
+        .or_else(preferred_by_name)
+        .or_else(|| ok_physical_devices.first())
+        .context("No compatible Vulkan-capable GPU found; is your graphics driver installed?")?;
+    log_device_summary(properties, *graphics_queue_family_index);
 
+    let queue_priorities = [1.0];
+    let queue_create_infos = [vk::DeviceQueueCreateInfo::builder()
+        .queue_family_index(*graphics_queue_family_index)
+        .queue_priorities(&queue_priorities)
+        .build()];
+    let mut enabled_extensions = required_extensions_names.to_vec();
+    // devices that only implement a subset of vulkan must have it enabled
+    let supports_portability_subset = instance
+        .enumerate_device_extension_properties(*physical_device)
+        .context("Failed getting the supported device extensions")?
+        .iter()
+        .any(|it| CStr::from_ptr(it.extension_name.as_ptr()) == vk::KhrPortabilitySubsetFn::name());
+    if supports_portability_subset {
+        enabled_extensions.push(vk::KhrPortabilitySubsetFn::name());
+    }
+    let enabled_extension_strings = enabled_extensions
+        .iter()
+        .map(|it| it.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    for extension_name in &enabled_extension_strings {
+        debug!("Enabled device extension: {}", extension_name);
+    }
+    let enabled_extension_names = enabled_extensions
+        .iter()
+        .map(|it| it.as_ptr())
+        .collect::<Vec<_>>();
     let device_create_info = vk::DeviceCreateInfo::builder()
-        .queue_create_infos(&[vk::DeviceQueueCreateInfo::builder()
-            .queue_family_index(*graphics_queue_family_index)
-            .queue_priorities(&[1.0])
-            .build()])
-        .enabled_extension_names(&required_extensions_names.map(|it| it.as_ptr()))
+        .queue_create_infos(&queue_create_infos)
+        .enabled_extension_names(&enabled_extension_names)
         .build();
     let device = instance
         .create_device(*physical_device, &device_create_info, None)
-        .expect("Could not create device.");
-    let queue = device.get_device_queue(*graphics_queue_family_index, 0);
+        .context("Could not create device.")?;
+
+    let queues = Queues {
+        graphics_family_index: *graphics_queue_family_index,
+        graphics: device.get_device_queue(*graphics_queue_family_index, 0),
+    };
+    Ok((*physical_device, device, queues, enabled_extension_strings))
+}
 
-    (*physical_device, device, queue)
+/// The queues requested from the device, together with the families they belong to.
+struct Queues {
+    graphics_family_index: u32,
+    graphics: vk::Queue,
+}
+
+/// Logs the most important properties of the selected device, to make bug reports easier to triage.
+unsafe fn log_device_summary(
+    properties: &vk::PhysicalDeviceProperties,
+    graphics_queue_family_index: u32,
+) {
+    info!(
+        "Using physical device: {}",
+        CStr::from_ptr(properties.device_name.as_ptr()).to_string_lossy()
+    );
+    info!("  Device type: {:?}", properties.device_type);
+    info!(
+        "  Vendor id: {:#06x}, device id: {:#06x}",
+        properties.vendor_id, properties.device_id
+    );
+    info!("  Driver version: {:#x}", properties.driver_version);
+    info!(
+        "  API version: {}.{}.{}",
+        vk::api_version_major(properties.api_version),
+        vk::api_version_minor(properties.api_version),
+        vk::api_version_patch(properties.api_version)
+    );
+    info!(
+        "  Max push constants size: {} bytes",
+        properties.limits.max_push_constants_size
+    );
+    info!(
+        "  Max bound descriptor sets: {}",
+        properties.limits.max_bound_descriptor_sets
+    );
+    info!("  Graphics queue family: {}", graphics_queue_family_index);
 }
 
 unsafe fn create_swapchain(
@@ -305,36 +1523,57 @@ unsafe fn create_swapchain(
     ext_swapchain: &khr::Swapchain,
     ext_surface: &khr::Surface,
     window: &Window,
-) -> (vk::SwapchainKHR, Vec<vk::ImageView>) {
+    settings: &RenderLoopSettings,
+) -> Result<(
+    vk::SwapchainKHR,
+    Vec<vk::ImageView>,
+    vk::SurfaceFormatKHR,
+    vk::Extent2D,
+)> {
     let surface_cap = ext_surface
         .get_physical_device_surface_capabilities(physical_device, surface)
-        .expect("Could not get surface capabilities");
+        .context("Could not get surface capabilities")?;
 
     let surface_formats = ext_surface
         .get_physical_device_surface_formats(physical_device, surface)
-        .expect("Could not get surface formats");
-    let surface_format = surface_formats.first().unwrap();
+        .context("Could not get surface formats")?;
+    // prefer an sRGB format, so that the final image gets the correct gamma
+    let surface_format = surface_formats
+        .iter()
+        .find(|it| {
+            it.format == vk::Format::B8G8R8A8_SRGB
+                && it.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+        })
+        .or_else(|| surface_formats.first())
+        .context("No supported surface formats")?;
+    info!(
+        "Using surface format: {:?} ({:?})",
+        surface_format.format, surface_format.color_space
+    );
 
     let surface_present_modes = ext_surface
         .get_physical_device_surface_present_modes(physical_device, surface)
-        .expect("Could not get surface presentation modes");
-
-    let presentation_mode = if surface_present_modes.contains(&vk::PresentModeKHR::FIFO) {
-        vk::PresentModeKHR::FIFO
-    } else if surface_present_modes.contains(&vk::PresentModeKHR::MAILBOX) {
-        vk::PresentModeKHR::MAILBOX
-    } else if surface_present_modes.contains(&vk::PresentModeKHR::FIFO_RELAXED) {
-        vk::PresentModeKHR::FIFO_RELAXED
-    } else if surface_present_modes.contains(&vk::PresentModeKHR::IMMEDIATE) {
-        vk::PresentModeKHR::IMMEDIATE
+        .context("Could not get surface presentation modes")?;
+
+    // FIFO is always supported, the others are only used when vsync is turned off
+    let preferred_present_modes: &[vk::PresentModeKHR] = if settings.vsync {
+        &[vk::PresentModeKHR::FIFO]
     } else {
-        *surface_present_modes
-            .first()
-            .expect("No supported presentation modes")
+        &[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX]
     };
+    let presentation_mode = preferred_present_modes
+        .iter()
+        .copied()
+        .find(|it| surface_present_modes.contains(it))
+        .unwrap_or(vk::PresentModeKHR::FIFO);
+    info!("Using presentation mode: {:?}", presentation_mode);
 
-    // image count: one more than surface_cap.min_image_count, unless surface_cap.max_image_count does not allow that
-    let mut image_count = (surface_cap.min_image_count + 1);
+    // image count: one more than surface_cap.min_image_count by default, but always within the
+    // limits of the surface. A max_image_count of 0 means there is no upper limit.
+    let mut image_count = settings
+        .swapchain_image_count
+        .unwrap_or(surface_cap.min_image_count + 1)
+        .max(surface_cap.min_image_count);
     if surface_cap.max_image_count != 0 {
         image_count = image_count.min(surface_cap.max_image_count);
     }
@@ -378,38 +1617,165 @@ unsafe fn create_swapchain(
         .build();
     let swapchain = ext_swapchain
         .create_swapchain(&swapchain_create_info, None)
-        .expect("Failed to create swapchain.");
+        .context("Failed to create swapchain.")?;
+
+    let swapchain_images = match ext_swapchain.get_swapchain_images(swapchain) {
+        Ok(swapchain_images) => swapchain_images,
+        Err(err) => {
+            ext_swapchain.destroy_swapchain(swapchain, None);
+            return Err(err).context("Could not get swapchain images");
+        }
+    };
+    // the driver may create more images than requested
+    info!(
+        "Using {} swapchain images ({} requested)",
+        swapchain_images.len(),
+        image_count
+    );
 
-    let swapchain_images = ext_swapchain
-        .get_swapchain_images(swapchain)
-        .expect("Could not get swapchain images");
+    let mut swapchain_image_views = Vec::with_capacity(swapchain_images.len());
+    for image in &swapchain_images {
+        let image_view_create_info = vk::ImageViewCreateInfo::builder()
+            .image(*image)
+            .format(surface_format.format)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .components(vk::ComponentMapping {
+                r: vk::ComponentSwizzle::R,
+                g: vk::ComponentSwizzle::G,
+                b: vk::ComponentSwizzle::B,
+                a: vk::ComponentSwizzle::A,
+            })
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .build();
 
-    let swapchain_image_views = swapchain_images
-        .iter()
-        .map(|image| {
-            let image_view_create_info = vk::ImageViewCreateInfo::builder()
-                .image(*image)
-                .format(surface_format.format)
-                .view_type(vk::ImageViewType::TYPE_2D)
-                .components(vk::ComponentMapping {
-                    r: vk::ComponentSwizzle::R,
-                    g: vk::ComponentSwizzle::G,
-                    b: vk::ComponentSwizzle::B,
-                    a: vk::ComponentSwizzle::A,
-                })
-                .subresource_range(vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                })
-                .build();
+        match device.create_image_view(&image_view_create_info, None) {
+            Ok(image_view) => swapchain_image_views.push(image_view),
+            Err(err) => {
+                destroy_swapchain(device, ext_swapchain, swapchain, &swapchain_image_views);
+                return Err(err).context("Could not create Image View for swapchain image.");
+            }
+        }
+    }
 
-            device.create_image_view(&image_view_create_info, None)
-        })
-        .collect::<Result<Vec<_>, vk::Result>>()
-        .expect("Could not create Image View for swapchain image.");
+    Ok((
+        swapchain,
+        swapchain_image_views,
+        *surface_format,
+        vk::Extent2D {
+            width: extent_x,
+            height: extent_y,
+        },
+    ))
+}
+
+/// Creates a render pass with a single color attachment, that is cleared and then presented.
+unsafe fn create_render_pass(device: &Device, format: vk::Format) -> Result<vk::RenderPass> {
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(format)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .build();
+    let color_attachment_refs = [vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }];
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(&color_attachment_refs)
+        .build();
+    // wait for the swapchain image to be acquired before writing to it
+    let dependency = vk::SubpassDependency::builder()
+        .src_subpass(vk::SUBPASS_EXTERNAL)
+        .dst_subpass(0)
+        .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags::empty())
+        .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+        .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+        .build();
+    let attachments = [color_attachment];
+    let subpasses = [subpass];
+    let dependencies = [dependency];
+    let render_pass_create_info = vk::RenderPassCreateInfo::builder()
+        .attachments(&attachments)
+        .subpasses(&subpasses)
+        .dependencies(&dependencies)
+        .build();
+    device
+        .create_render_pass(&render_pass_create_info, None)
+        .context("Could not create render pass.")
+}
 
-    (swapchain, swapchain_image_views)
+/// Creates one framebuffer for each swapchain image.
+unsafe fn create_framebuffers(
+    device: &Device,
+    render_pass: vk::RenderPass,
+    image_views: &[vk::ImageView],
+    extent: vk::Extent2D,
+) -> Result<Vec<vk::Framebuffer>> {
+    let mut framebuffers = Vec::with_capacity(image_views.len());
+    for image_view in image_views {
+        let attachments = [*image_view];
+        let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
+            .render_pass(render_pass)
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1)
+            .build();
+        match device.create_framebuffer(&framebuffer_create_info, None) {
+            Ok(framebuffer) => framebuffers.push(framebuffer),
+            Err(err) => {
+                for framebuffer in framebuffers {
+                    device.destroy_framebuffer(framebuffer, None);
+                }
+                return Err(err).context("Could not create framebuffer.");
+            }
+        }
+    }
+    Ok(framebuffers)
+}
+
+/// Destroys the swapchain together with the image views of its images.
+unsafe fn destroy_swapchain(
+    device: &Device,
+    ext_swapchain: &khr::Swapchain,
+    swapchain: vk::SwapchainKHR,
+    swapchain_image_views: &[vk::ImageView],
+) {
+    for image_view in swapchain_image_views {
+        device.destroy_image_view(*image_view, None);
+    }
+    ext_swapchain.destroy_swapchain(swapchain, None);
+}
+
+/// Creates `count` semaphores, destroying the ones already created if one fails.
+unsafe fn create_semaphores(device: &Device, count: usize) -> Result<Vec<vk::Semaphore>> {
+    let mut semaphores = Vec::with_capacity(count);
+    for _ in 0..count {
+        match device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) {
+            Ok(semaphore) => semaphores.push(semaphore),
+            Err(err) => {
+                destroy_semaphores(device, &semaphores);
+                return Err(err).context("Could not create semaphore.");
+            }
+        }
+    }
+    Ok(semaphores)
+}
+
+unsafe fn destroy_semaphores(device: &Device, semaphores: &[vk::Semaphore]) {
+    for semaphore in semaphores {
+        device.destroy_semaphore(*semaphore, None);
+    }
 }
@@ -1,2 +1,5 @@
 mod debug;
+mod fps_counter;
+mod gamepad;
+mod gpu_timer;
 pub mod main_loop;
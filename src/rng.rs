@@ -0,0 +1,51 @@
+//! Seedable random numbers, so that procedural content and AI behave the same in every run with
+//! the same seed.
+
+use crate::math::{Vec2, Vec3};
+use rand::distributions::uniform::{SampleRange, SampleUniform};
+use rand::{Rng as _, SeedableRng};
+use rand_pcg::Pcg32;
+use std::f32::consts::TAU;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Deterministic pseudo random number generator.
+pub struct Rng(Pcg32);
+
+impl Rng {
+    /// Generator that always produces the same sequence for the same seed.
+    pub fn from_seed(seed: u64) -> Self {
+        Rng(Pcg32::seed_from_u64(seed))
+    }
+
+    /// Seed that is different in every run, taken from the system clock.
+    pub fn seed_from_time() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64)
+    }
+
+    /// Uniformly distributed value in `range`, e.g. `rng.range(0..10)` or `rng.range(0.0..=1.0)`.
+    pub fn range<T: SampleUniform, R: SampleRange<T>>(&mut self, range: R) -> T {
+        self.0.gen_range(range)
+    }
+
+    /// `true` with the given probability between 0 and 1.
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.0.gen_bool(probability.clamp(0.0, 1.0))
+    }
+
+    /// Uniformly distributed direction in the plane.
+    pub fn unit_vec2(&mut self) -> Vec2 {
+        let angle = self.range(0.0..TAU);
+        Vec2::new(angle.cos(), angle.sin())
+    }
+
+    /// Uniformly distributed direction in space.
+    pub fn unit_vec3(&mut self) -> Vec3 {
+        // uniform height and angle around the Y axis give a uniform distribution on the sphere
+        let y: f32 = self.range(-1.0..=1.0);
+        let radius = (1.0 - y * y).sqrt();
+        let around = self.unit_vec2() * radius;
+        Vec3::new(around.x, y, around.y)
+    }
+}
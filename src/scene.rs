@@ -0,0 +1,157 @@
+//! Hierarchy of nodes whose transforms are relative to their parent, e.g. wheels attached to a car.
+
+use crate::math::{from_translation_rotation_scale, Mat4, Quat, Vec3};
+use anyhow::{bail, Result};
+
+/// Position, orientation and size of a node, relative to its parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl Transform {
+    /// Matrix that transforms from the node's space into its parent's space.
+    pub fn matrix(&self) -> Mat4 {
+        from_translation_rotation_scale(self.translation, self.rotation, self.scale)
+    }
+}
+
+/// Handle of a node in a [SceneGraph].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+struct Node {
+    transform: Transform,
+    parent: Option<NodeId>,
+}
+
+/// Hierarchy of transforms, e.g. wheels that are attached to the body of a car.
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        SceneGraph::default()
+    }
+
+    /// Adds a node, optionally as a child of an existing node.
+    pub fn add_node(&mut self, transform: Transform, parent: Option<NodeId>) -> NodeId {
+        self.nodes.push(Node { transform, parent });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Moves a node below a different parent, or to the top level if `parent` is `None`.
+    ///
+    /// Fails if this would make the node an ancestor of itself.
+    pub fn set_parent(&mut self, node: NodeId, parent: Option<NodeId>) -> Result<()> {
+        let mut ancestor = parent;
+        while let Some(id) = ancestor {
+            if id == node {
+                bail!(
+                    "Setting the parent of {:?} to {:?} would create a cycle",
+                    node,
+                    parent
+                );
+            }
+            ancestor = self.nodes[id.0].parent;
+        }
+        self.nodes[node.0].parent = parent;
+        Ok(())
+    }
+
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self.nodes[node.0].parent
+    }
+
+    pub fn transform(&self, node: NodeId) -> &Transform {
+        &self.nodes[node.0].transform
+    }
+
+    pub fn transform_mut(&mut self, node: NodeId) -> &mut Transform {
+        &mut self.nodes[node.0].transform
+    }
+
+    /// Matrix that transforms from the node's space into world space.
+    pub fn world_matrix(&self, node: NodeId) -> Mat4 {
+        let node = &self.nodes[node.0];
+        let local = node.transform.matrix();
+        match node.parent {
+            Some(parent) => self.world_matrix(parent) * local,
+            None => local,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(x: f32, y: f32, z: f32) -> Transform {
+        Transform {
+            translation: Vec3::new(x, y, z),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn set_parent_rejects_self_parent() {
+        let mut scene = SceneGraph::new();
+        let node = scene.add_node(Transform::default(), None);
+        assert!(scene.set_parent(node, Some(node)).is_err());
+        assert_eq!(scene.parent(node), None);
+    }
+
+    #[test]
+    fn set_parent_rejects_indirect_cycle() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(Transform::default(), None);
+        let child = scene.add_node(Transform::default(), Some(root));
+        let grandchild = scene.add_node(Transform::default(), Some(child));
+        assert!(scene.set_parent(root, Some(grandchild)).is_err());
+        assert_eq!(scene.parent(root), None);
+    }
+
+    #[test]
+    fn set_parent_to_top_level() {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(translation(1.0, 0.0, 0.0), None);
+        let child = scene.add_node(translation(0.0, 2.0, 0.0), Some(root));
+        scene.set_parent(child, None).unwrap();
+        assert_eq!(scene.parent(child), None);
+        assert_eq!(
+            scene.world_matrix(child).transform_point3(Vec3::ZERO),
+            Vec3::new(0.0, 2.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn world_matrix_composes_parents() {
+        let mut scene = SceneGraph::new();
+        let car = scene.add_node(
+            Transform {
+                translation: Vec3::new(10.0, 0.0, 0.0),
+                rotation: Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+                scale: Vec3::splat(2.0),
+            },
+            None,
+        );
+        let wheel = scene.add_node(translation(1.0, 0.0, 0.0), Some(car));
+        // scaled by 2, then rotated so that +X points to -Z, then moved by 10 along X
+        let position = scene.world_matrix(wheel).transform_point3(Vec3::ZERO);
+        assert!(position.abs_diff_eq(Vec3::new(10.0, 0.0, -2.0), 1e-5));
+    }
+}